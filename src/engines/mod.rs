@@ -0,0 +1,100 @@
+use std::error::Error;
+use std::fmt;
+use std::path::PathBuf;
+
+pub mod postgresql;
+
+use self::postgresql::Drift;
+
+/// A category of engine-level failure, derived from the backend's error code
+/// rather than its English message text so the CLI can report an actionable
+/// outcome instead of a locale-dependent string.
+#[derive(Debug)]
+pub enum EngineError {
+    /// Authentication or authorization was rejected by the server.
+    AuthError,
+    /// The migration bookkeeping table already exists (duplicate_table); lets
+    /// `create_migration_table` stay idempotent without inspecting messages.
+    TableAlreadyExists,
+    /// An integrity constraint (unique, foreign key, check, …) was violated.
+    ConstraintViolation,
+    /// A transient serialization failure or deadlock that the caller may retry.
+    Retriable,
+    /// Another runner already holds the migration advisory lock.
+    LockConflict,
+    /// Any error not recognised by the typed layer; carries the raw detail.
+    Other(String),
+}
+
+impl fmt::Display for EngineError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EngineError::AuthError => write!(f, "authentication failed: check credentials"),
+            EngineError::TableAlreadyExists => write!(f, "migration table already exists"),
+            EngineError::ConstraintViolation => write!(f, "constraint violation"),
+            EngineError::Retriable => write!(f, "transient serialization failure or deadlock"),
+            EngineError::LockConflict => write!(f, "another migration is already in progress"),
+            EngineError::Other(detail) => write!(f, "{}", detail),
+        }
+    }
+}
+
+impl Error for EngineError {}
+
+/// A database backend that can create its bookkeeping table and apply or roll
+/// back migrations.
+///
+/// Every method returns `Box<dyn Error>` on failure; backends that classify
+/// their errors return an `EngineError` inside the box so callers can match on
+/// the category (e.g. retry on `EngineError::Retriable`).
+pub trait SqlEngine {
+    /// Whether the backend can wrap DDL inside a transaction.
+    ///
+    /// The runner uses this to pick the transactional vs. autocommit path
+    /// automatically (PostgreSQL can; MySQL commits implicitly on DDL).
+    fn supports_transactional_ddl(&self) -> bool;
+
+    /// Create the migration bookkeeping table if it does not already exist.
+    fn create_migration_table(&mut self) -> Result<u64, Box<dyn Error>>;
+
+    /// Return every applied migration version, newest first.
+    fn get_migrations(&mut self) -> Result<Vec<String>, Box<dyn Error>>;
+
+    /// Return `(version, hash, file_name)` for every applied migration of the
+    /// given type, newest first.
+    fn get_migrations_with_hashes(&mut self, migration_type: &str) -> Result<Vec<(String, String, String)>, Box<dyn Error>>;
+
+    /// Compare applied migrations against the files on disk and report any
+    /// drift. `expected` carries `(version, content)` for every file currently
+    /// on disk; the engine recomputes each hash with the algorithm recorded for
+    /// that row. This intentionally differs from the backlog's
+    /// `(version, recomputed_hash, file_name)` shape — see the implementation.
+    fn verify_migrations(&mut self, expected: &[(String, String)]) -> Result<Vec<Drift>, Box<dyn Error>>;
+
+    /// Apply a single migration, choosing the transactional or autocommit path.
+    fn migrate(&mut self, file: &PathBuf, version: &str, migration_type: &str, migration: &str, skip_transaction: bool) -> Result<(), Box<dyn Error>>;
+
+    /// Roll back a single migration, choosing the transactional or autocommit path.
+    fn rollback(&mut self, file: &PathBuf, version: &str, migration: &str, skip_transaction: bool) -> Result<(), Box<dyn Error>>;
+
+    /// Open the single transaction that wraps a whole pending batch.
+    fn begin_batch(&mut self) -> Result<(), Box<dyn Error>>;
+
+    /// Apply one migration plus its bookkeeping row inside the open batch
+    /// transaction. On failure the batch is rolled back and the classified
+    /// error returned; replaying the batch on `EngineError::Retriable` is the
+    /// runner's responsibility.
+    fn migrate_in_batch(&mut self, file: &PathBuf, version: &str, migration_type: &str, migration: &str) -> Result<(), Box<dyn Error>>;
+
+    /// Commit the batch transaction opened by `begin_batch`.
+    fn commit_batch(&mut self) -> Result<(), Box<dyn Error>>;
+
+    /// Roll back the batch transaction opened by `begin_batch`.
+    fn rollback_batch(&mut self) -> Result<(), Box<dyn Error>>;
+
+    /// Take the migration concurrency lock before running a batch.
+    fn acquire_lock(&mut self) -> Result<(), Box<dyn Error>>;
+
+    /// Release the migration concurrency lock taken by `acquire_lock`.
+    fn release_lock(&mut self) -> Result<(), Box<dyn Error>>;
+}
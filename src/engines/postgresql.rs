@@ -4,10 +4,146 @@ use super::{SqlEngine, EngineError};
 use std::error::Error;
 use crate::helpers::get_relevant_line;
 use std::path::PathBuf;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
 use md5;
+use sha2::{Sha256, Digest};
 use native_tls::TlsConnector;
 use postgres_native_tls::MakeTlsConnector;
 
+/// A checksum algorithm used to fingerprint migration files.
+///
+/// md5 is kept as the default for backwards compatibility with tables created
+/// before this column existed; teams that want a stronger guarantee can opt
+/// into SHA-256. The chosen algorithm is stored alongside every hash so drift
+/// checks know how to recompute it.
+#[derive(Clone, Copy)]
+pub enum HashAlgo {
+    Md5,
+    Sha256,
+}
+
+impl HashAlgo {
+    /// The name stored in the `hash_algo` column.
+    fn name(&self) -> &'static str {
+        match self {
+            HashAlgo::Md5 => "md5",
+            HashAlgo::Sha256 => "sha256",
+        }
+    }
+
+    /// Resolve the name stored in the `hash_algo` column back into a `HashAlgo`.
+    ///
+    /// Unknown or legacy values fall back to md5, which is what rows predating
+    /// the column were stamped with by `create_migration_table`.
+    fn from_name(name: &str) -> HashAlgo {
+        match name {
+            "sha256" => HashAlgo::Sha256,
+            _ => HashAlgo::Md5,
+        }
+    }
+
+    /// Compute the hex-encoded checksum of a migration's content.
+    fn compute(&self, content: &str) -> String {
+        match self {
+            HashAlgo::Md5 => format!("{:x}", md5::compute(content)),
+            HashAlgo::Sha256 => {
+                let mut hasher = Sha256::new();
+                hasher.update(content.as_bytes());
+                format!("{:x}", hasher.finalize())
+            }
+        }
+    }
+}
+
+/// A discrepancy between the applied migrations recorded in the database and
+/// the migration files currently on disk, as reported by `verify_migrations`.
+pub enum Drift {
+    /// A previously-applied migration's file content no longer matches the hash
+    /// stored when it was applied (the file was edited after the fact).
+    HashMismatch { version: String, stored_hash: String, current_hash: String },
+    /// An applied version whose migration file has vanished from disk.
+    MissingFile { version: String, file_name: String },
+    /// A file on disk that sorts before an already-applied migration but has
+    /// not itself been applied (an out-of-order insertion).
+    OutOfOrder { version: String },
+}
+
+/// Derive a stable 64-bit advisory-lock key from the migration table name.
+///
+/// PostgreSQL advisory locks are keyed by a `bigint`; hashing the table name
+/// keeps concurrent runners that share a table contending on the same key while
+/// runners with different tables stay independent.
+///
+/// # Arguments
+///
+/// * `migration_table_name` - The name of the migration bookkeeping table.
+fn advisory_lock_key(migration_table_name: &str) -> i64 {
+    let digest = md5::compute(migration_table_name);
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&digest[0..8]);
+    i64::from_be_bytes(bytes)
+}
+
+/// Classify a PostgreSQL error by its five-character SQLSTATE code.
+///
+/// Inspecting the code is locale-independent, unlike matching on the English
+/// message text. Errors that do not carry a `DbError` (connection/protocol
+/// failures) or an unknown code fall back to `EngineError::Other`.
+///
+/// # Arguments
+///
+/// * `error` - The PostgreSQL error to classify.
+fn classify_postgres_error(error: &postgres::error::Error) -> EngineError {
+    let source: Option<&(dyn std::error::Error + 'static)> = error.source();
+    let downcast = source.and_then(|e| e.downcast_ref::<postgres::error::DbError>());
+    let downcast = match downcast {
+        Some(d) => d,
+        None => return EngineError::Other(format!("{}", error)),
+    };
+    classify_sqlstate(downcast.code().code())
+}
+
+/// How many times in all a migration transaction is attempted, the first try
+/// included, while it keeps aborting with a serialization failure or deadlock,
+/// before the error is surfaced to the caller.
+const MAX_MIGRATION_ATTEMPTS: u32 = 5;
+
+/// Map a SQLSTATE code to an `EngineError` variant.
+///
+/// Individual codes are matched first, then whole classes, so that any
+/// `28xxx` authorization failure or `23xxx` integrity-constraint violation is
+/// recognised without enumerating every code in the class.
+///
+/// # Arguments
+///
+/// * `code` - The five-character SQLSTATE code.
+fn classify_sqlstate(code: &str) -> EngineError {
+    match code {
+        "42P07" => EngineError::TableAlreadyExists,
+        "40001" | "40P01" => EngineError::Retriable,
+        c if c.starts_with("28") => EngineError::AuthError,
+        c if c.starts_with("23") => EngineError::ConstraintViolation,
+        other => EngineError::Other(other.to_owned()),
+    }
+}
+
+/// Whether a migration opts out of running inside a transaction.
+///
+/// Some statements (e.g. `CREATE INDEX CONCURRENTLY`) are rejected by
+/// PostgreSQL when executed inside a transaction block. Such a file can carry a
+/// `-- migrate:no-transaction` directive on any line to force the autocommit
+/// path regardless of the backend's `supports_transactional_ddl`. The directive
+/// is checked by `migrate` and `rollback` only; `migrate_in_batch` does not
+/// check it and runs every file inside the batch transaction.
+///
+/// # Arguments
+///
+/// * `migration` - The raw SQL content of the migration file.
+fn migration_skips_transaction(migration: &str) -> bool {
+    migration.lines().any(|line| line.trim() == "-- migrate:no-transaction")
+}
+
 /// Print on console the PostgreSQL error.
 ///
 /// # Arguments
@@ -81,6 +217,8 @@ fn print_error_postgres(content: &str, error: postgres::error::Error) {
 pub struct Postgresql {
     client: Client,
     migration_table_name: String,
+    hash_algo: HashAlgo,
+    lock_timeout: Option<Duration>,
 }
 
 impl Postgresql {
@@ -108,10 +246,13 @@ impl Postgresql {
             connection = config.connect(connector);
             if connection.is_err() {
                 let err = connection.err().unwrap();
-                if err.to_string().starts_with("error parsing response from server") {
-                    crit!("Could not connect to PostgreSQL: check credentials");
-                } else {
-                    crit!("Could not connect to PostgreSQL: {}", err.to_string());
+                match classify_postgres_error(&err) {
+                    EngineError::AuthError => {
+                        crit!("Could not connect to PostgreSQL: check credentials");
+                    }
+                    _ => {
+                        crit!("Could not connect to PostgreSQL: {}", err.to_string());
+                    }
                 }
                 return Err(Box::new(err));
             }
@@ -120,19 +261,76 @@ impl Postgresql {
         Ok(Box::new(Postgresql {
             client: connection.unwrap(),
             migration_table_name: migration_table_name.to_owned(),
+            hash_algo: HashAlgo::Md5,
+            lock_timeout: None,
         }))
     }
+
+    /// Select the checksum algorithm used for new migrations.
+    ///
+    /// Defaults to md5; call with `HashAlgo::Sha256` to opt into SHA-256.
+    /// `Postgresql::new` returns a boxed `SqlEngine`, which this builder cannot
+    /// be applied to, so the engine returned by `new` always uses md5.
+    pub fn with_hash_algo(mut self, hash_algo: HashAlgo) -> Self {
+        self.hash_algo = hash_algo;
+        self
+    }
+
+    /// How long `acquire_lock` keeps retrying the advisory lock before giving up.
+    ///
+    /// With no timeout (the default) a single `pg_try_advisory_lock` attempt is
+    /// made and a busy lock fails fast with `EngineError::LockConflict`. With a
+    /// timeout set, the attempt is retried until the deadline so a runner that
+    /// starts slightly ahead of another can wait out a short overlap instead of
+    /// failing immediately. `Postgresql::new` returns a boxed `SqlEngine`, which
+    /// this builder cannot be applied to, so the engine returned by `new` has no
+    /// timeout.
+    pub fn with_lock_timeout(mut self, timeout: Duration) -> Self {
+        self.lock_timeout = Some(timeout);
+        self
+    }
 }
 
 impl SqlEngine for Postgresql {
+    fn supports_transactional_ddl(&self) -> bool {
+        // PostgreSQL can wrap CREATE/ALTER statements in a transaction, so the
+        // transactional path is safe by default (MySQL, by contrast, commits
+        // implicitly on DDL).
+        true
+    }
+
     fn create_migration_table(&mut self) -> Result<u64, Box<dyn Error>> {
-        let mut create_table: String = String::from("CREATE TABLE IF NOT EXISTS \"");
+        let mut create_table: String = String::from("CREATE TABLE \"");
         create_table.push_str(&self.migration_table_name);
-        create_table.push_str("\" (\"migration\" TEXT PRIMARY KEY, \"hash\" TEXT, \"type\" TEXT, \"file_name\" TEXT, \"created_at\" TIMESTAMP)");
-        match self.client.execute(&create_table as &str, &[]) {
-            Ok(i) => Ok(i),
-            Err(e) => Err(Box::new(e))
+        create_table.push_str("\" (\"migration\" TEXT PRIMARY KEY, \"hash\" TEXT, \"hash_algo\" TEXT, \"type\" TEXT, \"file_name\" TEXT, \"created_at\" TIMESTAMP)");
+        let created = match self.client.execute(&create_table as &str, &[]) {
+            Ok(i) => i,
+            // A `42P07` (duplicate_table) means the table is already there: treat
+            // the create as a successful no-op so the call stays idempotent
+            // without relying on `IF NOT EXISTS` or on message text.
+            Err(e) => match classify_postgres_error(&e) {
+                EngineError::TableAlreadyExists => 0,
+                _ => return Err(Box::new(e)),
+            }
+        };
+
+        // Bring tables created before the column existed up to date: add the
+        // column and stamp legacy rows (which could only have been md5) so the
+        // drift check never sees a NULL algorithm.
+        let mut add_column = String::from("ALTER TABLE \"");
+        add_column.push_str(&self.migration_table_name);
+        add_column.push_str("\" ADD COLUMN IF NOT EXISTS \"hash_algo\" TEXT");
+        if let Err(e) = self.client.execute(&add_column as &str, &[]) {
+            return Err(Box::new(e));
+        }
+        let mut backfill = String::from("UPDATE \"");
+        backfill.push_str(&self.migration_table_name);
+        backfill.push_str("\" SET \"hash_algo\" = 'md5' WHERE \"hash_algo\" IS NULL");
+        if let Err(e) = self.client.execute(&backfill as &str, &[]) {
+            return Err(Box::new(e));
         }
+
+        Ok(created)
     }
 
     fn get_migrations(&mut self) -> Result<Vec<String>, Box<dyn Error>> {
@@ -169,27 +367,103 @@ impl SqlEngine for Postgresql {
         Ok(results)
     }
 
+    fn verify_migrations(&mut self, expected: &[(String, String)]) -> Result<Vec<Drift>, Box<dyn Error>> {
+        // Pull every applied row together with the hash recorded at apply time
+        // and the algorithm that produced it, so each row is re-checked with its
+        // own algorithm even once the repo has switched the engine default to a
+        // stronger one.
+        //
+        // `expected` carries `(version, content)` per on-disk file. This differs
+        // from the backlog's `(version, recomputed_hash, file_name)`: we must
+        // recompute the hash with each row's *stored* algorithm rather than a
+        // single caller-computed one, and the file name is read from the DB row
+        // (the only place it is known when a file has vanished), so neither of
+        // those elements belongs in `expected`.
+        let mut get_migration = String::from("SELECT \"migration\", \"hash\", \"hash_algo\", \"file_name\" FROM \"");
+        get_migration.push_str(&self.migration_table_name);
+        get_migration.push_str("\" ORDER BY \"migration\" asc");
+        let data = self.client.query(&get_migration as &str, &[]);
+        if data.is_err() {
+            let err = data.err().unwrap();
+            crit!("Error getting migration: {}", err.to_string());
+            return Err(Box::new(err));
+        }
+        // version, stored hash, stored algorithm, file name
+        let mut applied: Vec<(String, String, String, String)> = Vec::new();
+        for row in data.unwrap() {
+            // Legacy rows could predate the column and read back NULL; those can
+            // only have been md5.
+            let stored_algo: Option<String> = row.get(2);
+            applied.push((row.get(0), row.get(1), stored_algo.unwrap_or_else(|| "md5".to_owned()), row.get(3)));
+        }
+
+        let mut drifts: Vec<Drift> = Vec::new();
+
+        // Every applied migration must still be on disk with a matching hash,
+        // recomputed with the algorithm recorded for that row.
+        for (version, stored_hash, stored_algo, file_name) in &applied {
+            match expected.iter().find(|(v, _)| v == version) {
+                Some((_, content)) => {
+                    let current_hash = HashAlgo::from_name(stored_algo).compute(content);
+                    if &current_hash != stored_hash {
+                        drifts.push(Drift::HashMismatch {
+                            version: version.to_owned(),
+                            stored_hash: stored_hash.to_owned(),
+                            current_hash,
+                        });
+                    }
+                }
+                None => drifts.push(Drift::MissingFile {
+                    version: version.to_owned(),
+                    file_name: file_name.to_owned(),
+                }),
+            }
+        }
+
+        // Any on-disk file that sorts before the latest applied migration but
+        // has not been applied itself is an out-of-order insertion.
+        if let Some((max_applied, _, _, _)) = applied.last() {
+            for (version, _) in expected {
+                let is_applied = applied.iter().any(|(v, _, _, _)| v == version);
+                if !is_applied && version.as_str() < max_applied.as_str() {
+                    drifts.push(Drift::OutOfOrder { version: version.to_owned() });
+                }
+            }
+        }
+
+        Ok(drifts)
+    }
+
     fn migrate(&mut self, file: &PathBuf, version: &str, migration_type: &str, migration: &str, skip_transaction: bool) -> Result<(), Box<dyn Error>> {
+        // The autocommit path is taken when the backend cannot wrap DDL in a
+        // transaction, or when a `-- migrate:no-transaction` directive in the
+        // file opts this migration out, or when the caller forces it.
+        let skip_transaction = skip_transaction
+            || !self.supports_transactional_ddl()
+            || migration_skips_transaction(migration);
+
         // Insert statement
         let mut insert = String::from("INSERT INTO \"");
         insert.push_str(&self.migration_table_name);
-        insert.push_str("\" (\"migration\", \"hash\", \"type\", \"file_name\", \"created_at\") VALUES ($1, $2, $3, $4, NOW());");
+        insert.push_str("\" (\"migration\", \"hash\", \"hash_algo\", \"type\", \"file_name\", \"created_at\") VALUES ($1, $2, $3, $4, $5, NOW());");
 
         if skip_transaction {
             // Inserting migration
             match self.client.batch_execute(migration) {
                 Ok(_) => {},
                 Err(e) => {
+                    let engine_error = classify_postgres_error(&e);
                     print_error_postgres(migration, e);
-                    return Err(Box::new(EngineError {}));
+                    return Err(Box::new(engine_error));
                 }
             };
 
-            let hash = format!("{:x}", md5::compute(&migration));
+            let hash = self.hash_algo.compute(migration);
+            let hash_algo = self.hash_algo.name();
             let file_name = format!("{}", &file.display());
 
             // Store in migration table and commit
-            match self.client.query(&insert as &str, &[&version, &hash, &migration_type, &file_name]) {
+            match self.client.query(&insert as &str, &[&version, &hash, &hash_algo, &migration_type, &file_name]) {
                 Ok(_) => Ok(()),
                 Err(e) => {
                     crit!("Could store result in migration table: {}", e.to_string());
@@ -198,46 +472,73 @@ impl SqlEngine for Postgresql {
             }
 
         } else {
-            // Do the transaction
-            let trx = self.client.transaction();
-            if trx.is_err() {
-                let err = trx.err().unwrap();
-                crit!("Could not create a transaction: {}", err.to_string());
-                return Err(Box::new(err));
-            }
+            // A serialization failure or deadlock aborts the whole transaction;
+            // the documented remedy is to replay it from the top, which we do a
+            // bounded number of times before giving up.
+            let mut attempts = 0u32;
+            loop {
+                attempts += 1;
 
-            // Executing migration
-            let mut trx = trx.unwrap();
-            match trx.batch_execute(migration) {
-                Ok(_) => {},
-                Err(e) => {
-                    print_error_postgres(migration, e);
-                    return Err(Box::new(EngineError {}));
+                // Do the transaction
+                let trx = self.client.transaction();
+                if trx.is_err() {
+                    let err = trx.err().unwrap();
+                    crit!("Could not create a transaction: {}", err.to_string());
+                    return Err(Box::new(err));
                 }
-            };
 
-            let hash = format!("{:x}", md5::compute(&migration));
-            let file_name = format!("{}", &file.display());
+                // Executing migration
+                let mut trx = trx.unwrap();
+                match trx.batch_execute(migration) {
+                    Ok(_) => {},
+                    Err(e) => {
+                        let engine_error = classify_postgres_error(&e);
+                        if matches!(engine_error, EngineError::Retriable) && attempts < MAX_MIGRATION_ATTEMPTS {
+                            continue;
+                        }
+                        print_error_postgres(migration, e);
+                        return Err(Box::new(engine_error));
+                    }
+                };
 
-            // Store in migration table and commit
-            match trx.query(&insert as &str, &[&version, &hash, &migration_type, &file_name]) {
-                Ok(_) => {},
-                Err(e) => {
-                    crit!("Could store result in migration table: {}", e.to_string());
-                    return Err(Box::new(e));
-                }
-            };
-            match trx.commit() {
-                Ok(_) => Ok(()),
-                Err(e) => {
-                    crit!("Failed to commit transaction: {}", e.to_string());
-                    Err(Box::new(e))
+                let hash = self.hash_algo.compute(migration);
+                let hash_algo = self.hash_algo.name();
+                let file_name = format!("{}", &file.display());
+
+                // Store in migration table and commit
+                match trx.query(&insert as &str, &[&version, &hash, &hash_algo, &migration_type, &file_name]) {
+                    Ok(_) => {},
+                    Err(e) => {
+                        if matches!(classify_postgres_error(&e), EngineError::Retriable) && attempts < MAX_MIGRATION_ATTEMPTS {
+                            continue;
+                        }
+                        crit!("Could store result in migration table: {}", e.to_string());
+                        return Err(Box::new(e));
+                    }
+                };
+                match trx.commit() {
+                    Ok(_) => return Ok(()),
+                    Err(e) => {
+                        let engine_error = classify_postgres_error(&e);
+                        if matches!(engine_error, EngineError::Retriable) && attempts < MAX_MIGRATION_ATTEMPTS {
+                            continue;
+                        }
+                        crit!("Failed to commit transaction: {}", e.to_string());
+                        return Err(Box::new(e));
+                    }
                 }
             }
         }
     }
 
     fn rollback(&mut self, _file: &PathBuf, version: &str, migration: &str, skip_transaction: bool) -> Result<(), Box<dyn Error>> {
+        // The autocommit path is taken when the backend cannot wrap DDL in a
+        // transaction, or when a `-- migrate:no-transaction` directive in the
+        // file opts this migration out, or when the caller forces it.
+        let skip_transaction = skip_transaction
+            || !self.supports_transactional_ddl()
+            || migration_skips_transaction(migration);
+
         // Delete statement
         let mut del = String::from("DELETE FROM \"");
         del.push_str(&self.migration_table_name);
@@ -248,8 +549,9 @@ impl SqlEngine for Postgresql {
             match self.client.batch_execute(migration) {
                 Ok(_) => {},
                 Err(e) => {
+                    let engine_error = classify_postgres_error(&e);
                     print_error_postgres(migration, e);
-                    return Err(Box::new(EngineError {}));
+                    return Err(Box::new(engine_error));
                 }
             };
 
@@ -276,8 +578,9 @@ impl SqlEngine for Postgresql {
             match trx.batch_execute(migration) {
                 Ok(_) => {},
                 Err(e) => {
+                    let engine_error = classify_postgres_error(&e);
                     print_error_postgres(migration, e);
-                    return Err(Box::new(EngineError {}));
+                    return Err(Box::new(engine_error));
                 }
             };
 
@@ -298,4 +601,163 @@ impl SqlEngine for Postgresql {
             }
         }
     }
+
+    fn begin_batch(&mut self) -> Result<(), Box<dyn Error>> {
+        match self.client.batch_execute("BEGIN") {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                crit!("Could not open batch transaction: {}", e.to_string());
+                Err(Box::new(e))
+            }
+        }
+    }
+
+    fn migrate_in_batch(&mut self, file: &PathBuf, version: &str, migration_type: &str, migration: &str) -> Result<(), Box<dyn Error>> {
+        // Insert statement
+        let mut insert = String::from("INSERT INTO \"");
+        insert.push_str(&self.migration_table_name);
+        insert.push_str("\" (\"migration\", \"hash\", \"hash_algo\", \"type\", \"file_name\", \"created_at\") VALUES ($1, $2, $3, $4, $5, NOW());");
+
+        // Executing migration inside the already open batch transaction. On any
+        // failure we roll the whole batch back so the connection is not left in
+        // an aborted transaction and no partial work is committed.
+        //
+        // A serialization failure or deadlock (`EngineError::Retriable`) aborts
+        // the entire batch transaction, not just this statement, so it cannot be
+        // retried here the way the single-file `migrate` path retries its own
+        // self-contained transaction. Retrying is the runner's responsibility:
+        // it drives `begin_batch`/`migrate_in_batch`/`commit_batch` and should
+        // replay the whole batch when any call returns `EngineError::Retriable`.
+        // We surface the classified error (not the raw `postgres::Error`) so the
+        // runner can downcast and make that decision.
+        match self.client.batch_execute(migration) {
+            Ok(_) => {},
+            Err(e) => {
+                let engine_error = classify_postgres_error(&e);
+                print_error_postgres(migration, e);
+                let _ = self.client.batch_execute("ROLLBACK");
+                return Err(Box::new(engine_error));
+            }
+        };
+
+        let hash = self.hash_algo.compute(migration);
+        let hash_algo = self.hash_algo.name();
+        let file_name = format!("{}", &file.display());
+
+        // Store the bookkeeping row, still inside the batch transaction
+        match self.client.query(&insert as &str, &[&version, &hash, &hash_algo, &migration_type, &file_name]) {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                crit!("Could store result in migration table: {}", e.to_string());
+                let engine_error = classify_postgres_error(&e);
+                let _ = self.client.batch_execute("ROLLBACK");
+                Err(Box::new(engine_error))
+            }
+        }
+    }
+
+    fn commit_batch(&mut self) -> Result<(), Box<dyn Error>> {
+        match self.client.batch_execute("COMMIT") {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                crit!("Failed to commit batch transaction: {}", e.to_string());
+                Err(Box::new(e))
+            }
+        }
+    }
+
+    fn rollback_batch(&mut self) -> Result<(), Box<dyn Error>> {
+        match self.client.batch_execute("ROLLBACK") {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                crit!("Failed to roll back batch transaction: {}", e.to_string());
+                Err(Box::new(e))
+            }
+        }
+    }
+
+    fn acquire_lock(&mut self) -> Result<(), Box<dyn Error>> {
+        // `pg_try_advisory_lock` returns immediately rather than blocking. With
+        // no `lock_timeout` configured a single attempt is made, so a second
+        // runner fails fast with a clear message instead of hanging; with a
+        // timeout configured the attempt is retried until the deadline.
+        let key = advisory_lock_key(&self.migration_table_name);
+        let deadline = self.lock_timeout.map(|t| Instant::now() + t);
+        // Poll often enough to feel responsive without hammering the server.
+        let poll_interval = Duration::from_millis(250);
+        loop {
+            let data = self.client.query("SELECT pg_try_advisory_lock($1)", &[&key]);
+            if data.is_err() {
+                let err = data.err().unwrap();
+                crit!("Could not acquire migration lock: {}", err.to_string());
+                return Err(Box::new(err));
+            }
+            let locked: bool = data.unwrap()[0].get(0);
+            if locked {
+                return Ok(());
+            }
+
+            match deadline {
+                // Still time left on the clock: wait a little and try again,
+                // without overshooting the deadline.
+                Some(deadline) if Instant::now() < deadline => {
+                    let remaining = deadline - Instant::now();
+                    sleep(remaining.min(poll_interval));
+                }
+                _ => {
+                    crit!("Another migration is already in progress");
+                    return Err(Box::new(EngineError::LockConflict));
+                }
+            }
+        }
+    }
+
+    fn release_lock(&mut self) -> Result<(), Box<dyn Error>> {
+        // A failed batch can leave the session in an aborted transaction, in
+        // which case the unlock query below would itself fail with `25P02`
+        // ("current transaction is aborted") and the session-level lock would
+        // leak for the life of the connection. Clear any such state first with a
+        // best-effort ROLLBACK; session-level advisory locks survive it, so the
+        // lock is still held and the unlock below actually releases it.
+        let _ = self.client.batch_execute("ROLLBACK");
+
+        let key = advisory_lock_key(&self.migration_table_name);
+        match self.client.query("SELECT pg_advisory_unlock($1)", &[&key]) {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                crit!("Could not release migration lock: {}", e.to_string());
+                Err(Box::new(e))
+            }
+        }
+    }
+}
+
+/// RAII guard that holds a migration advisory lock for its lifetime.
+///
+/// Acquiring through `AdvisoryLockGuard::new` takes the lock up front; the lock
+/// is released in `Drop`, so it is given back on the success path, on an early
+/// `?` return, and while a panic unwinds the batch.
+pub struct AdvisoryLockGuard<'a> {
+    engine: &'a mut dyn SqlEngine,
+}
+
+impl<'a> AdvisoryLockGuard<'a> {
+    /// Acquire the advisory lock, returning a guard that releases it on drop.
+    pub fn new(engine: &'a mut dyn SqlEngine) -> Result<AdvisoryLockGuard<'a>, Box<dyn Error>> {
+        engine.acquire_lock()?;
+        Ok(AdvisoryLockGuard { engine })
+    }
+
+    /// Borrow the locked engine to run the migration batch.
+    pub fn engine(&mut self) -> &mut dyn SqlEngine {
+        self.engine
+    }
+}
+
+impl<'a> Drop for AdvisoryLockGuard<'a> {
+    fn drop(&mut self) {
+        if let Err(e) = self.engine.release_lock() {
+            crit!("Could not release migration lock: {}", e.to_string());
+        }
+    }
 }